@@ -0,0 +1,6 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Vendor protoc instead of requiring operators/CI to have it installed.
+    std::env::set_var("PROTOC", protobuf_src::protoc());
+    tonic_build::compile_protos("proto/editor.proto")?;
+    Ok(())
+}
@@ -0,0 +1,184 @@
+/// Payloads at or under this size are sent as a single chunk (no framing
+/// overhead); anything bigger is split so it can't stall the per-client
+/// send loop or block out smaller messages (e.g. awareness updates)
+/// queued behind it.
+pub const DEFAULT_CHUNK_THRESHOLD: usize = 16 * 1024;
+
+/// Small header ordering the chunks of one logical message back together.
+/// `msg_id` distinguishes messages that happen to be in flight on the same
+/// channel at once; `seq`/`last` let the receiver detect gaps and know
+/// when it has the whole thing.
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkHeader {
+    pub msg_id: u32,
+    pub seq: u16,
+    pub last: bool,
+}
+
+/// Splits `payload` into ordered `(header, bytes)` chunks of at most
+/// `threshold` bytes each. A payload at or under `threshold` comes back as
+/// a single chunk.
+pub fn chunk(payload: Vec<u8>, msg_id: u32, threshold: usize) -> Vec<(ChunkHeader, Vec<u8>)> {
+    if payload.len() <= threshold {
+        return vec![(ChunkHeader { msg_id, seq: 0, last: true }, payload)];
+    }
+
+    let total = payload.len();
+    payload
+        .chunks(threshold)
+        .enumerate()
+        .map(|(seq, bytes)| {
+            let last = (seq + 1) * threshold >= total;
+            (ChunkHeader { msg_id, seq: seq as u16, last }, bytes.to_vec())
+        })
+        .collect()
+}
+
+/// Reassembles the chunks of one message at a time for a single channel.
+/// Two channels carrying independent chunk streams (e.g. the room
+/// broadcast and a client's direct replies) need their own `Reassembler`,
+/// since this one only tracks a single message in flight.
+///
+/// This is meant for whoever is on the other end of the wire from
+/// [`encode_frame`] (a browser or native client): the server itself sends
+/// one [`Message::Binary`](axum::extract::ws::Message::Binary) per chunk
+/// and never reassembles outbound chunks, so the frame it writes is never
+/// bigger than [`DEFAULT_CHUNK_THRESHOLD`].
+// Reassembly happens on the other end of the wire (browser/native client),
+// not in this server binary, so nothing here calls these outside the tests
+// below; kept (and tested) as the reference implementation for that side.
+#[allow(dead_code)]
+#[derive(Default)]
+pub struct Reassembler {
+    in_progress: Option<(u32, Vec<u8>)>,
+}
+
+#[allow(dead_code)]
+impl Reassembler {
+    /// Feeds in one chunk. Returns the reassembled payload once `last` is
+    /// seen; `None` while more chunks are still expected.
+    pub fn push(&mut self, header: ChunkHeader, bytes: Vec<u8>) -> Option<Vec<u8>> {
+        let (msg_id, buf) = self.in_progress.get_or_insert_with(|| (header.msg_id, Vec::new()));
+
+        if *msg_id != header.msg_id {
+            tracing::warn!(
+                "chunking: got chunk for msg_id {} while still reassembling {}, discarding in-progress buffer",
+                header.msg_id,
+                msg_id
+            );
+            *msg_id = header.msg_id;
+            buf.clear();
+        }
+
+        buf.extend_from_slice(&bytes);
+
+        if header.last {
+            let (_, buf) = self.in_progress.take().unwrap();
+            Some(buf)
+        } else {
+            None
+        }
+    }
+}
+
+const FRAME_HEADER_LEN: usize = 4 + 2 + 1;
+
+/// Encodes a chunk as a single self-contained wire frame: `[msg_id: u32
+/// LE][seq: u16 LE][last: u8][bytes...]`. Each chunk is sent as its own
+/// `Message::Binary`, so the receiver needs the header alongside the bytes
+/// to know how to reassemble them; see [`decode_frame`].
+pub fn encode_frame(header: ChunkHeader, bytes: Vec<u8>) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + bytes.len());
+    frame.extend_from_slice(&header.msg_id.to_le_bytes());
+    frame.extend_from_slice(&header.seq.to_le_bytes());
+    frame.push(header.last as u8);
+    frame.extend_from_slice(&bytes);
+    frame
+}
+
+/// Decodes a frame produced by [`encode_frame`] back into its header and
+/// payload. `None` if `frame` is shorter than the fixed header.
+#[allow(dead_code)]
+pub fn decode_frame(frame: &[u8]) -> Option<(ChunkHeader, Vec<u8>)> {
+    if frame.len() < FRAME_HEADER_LEN {
+        return None;
+    }
+    let (head, bytes) = frame.split_at(FRAME_HEADER_LEN);
+    let msg_id = u32::from_le_bytes(head[0..4].try_into().unwrap());
+    let seq = u16::from_le_bytes(head[4..6].try_into().unwrap());
+    let last = head[6] != 0;
+    Some((ChunkHeader { msg_id, seq, last }, bytes.to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chunk_under_threshold_is_a_single_last_chunk() {
+        let payload = vec![1, 2, 3];
+        let chunks = chunk(payload.clone(), 7, 16);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].0.msg_id, 7);
+        assert_eq!(chunks[0].0.seq, 0);
+        assert!(chunks[0].0.last);
+        assert_eq!(chunks[0].1, payload);
+    }
+
+    #[test]
+    fn chunk_and_reassemble_round_trip() {
+        let payload: Vec<u8> = (0..100u16).flat_map(u16::to_le_bytes).collect();
+        let chunks = chunk(payload.clone(), 1, 16);
+        assert!(chunks.len() > 1);
+
+        let mut reassembler = Reassembler::default();
+        let mut out = None;
+        for (header, bytes) in chunks {
+            out = reassembler.push(header, bytes);
+        }
+        assert_eq!(out, Some(payload));
+    }
+
+    #[test]
+    fn encode_decode_frame_round_trip() {
+        let payload = vec![9, 8, 7, 6];
+        for (header, bytes) in chunk(payload, 42, 2) {
+            let frame = encode_frame(header, bytes.clone());
+            let (decoded_header, decoded_bytes) = decode_frame(&frame).unwrap();
+            assert_eq!(decoded_header.msg_id, header.msg_id);
+            assert_eq!(decoded_header.seq, header.seq);
+            assert_eq!(decoded_header.last, header.last);
+            assert_eq!(decoded_bytes, bytes);
+        }
+    }
+
+    #[test]
+    fn decode_frame_rejects_short_input() {
+        assert!(decode_frame(&[0, 1, 2]).is_none());
+    }
+
+    #[test]
+    fn reassembler_discards_in_progress_buffer_on_interleave() {
+        let mut reassembler = Reassembler::default();
+
+        // First chunk of message 1, not yet complete.
+        assert_eq!(
+            reassembler.push(ChunkHeader { msg_id: 1, seq: 0, last: false }, vec![1, 2]),
+            None
+        );
+
+        // A chunk for a different message arrives before message 1 finished:
+        // the partial buffer for message 1 is discarded, not merged into it.
+        assert_eq!(
+            reassembler.push(ChunkHeader { msg_id: 2, seq: 0, last: false }, vec![9]),
+            None
+        );
+        let result = reassembler.push(ChunkHeader { msg_id: 2, seq: 1, last: true }, vec![9]);
+        assert_eq!(result, Some(vec![9, 9]));
+
+        // The rest of message 1, had it arrived, would start a fresh buffer
+        // rather than silently resuming the discarded one.
+        let result = reassembler.push(ChunkHeader { msg_id: 1, seq: 1, last: true }, vec![3, 4]);
+        assert_eq!(result, Some(vec![3, 4]));
+    }
+}
@@ -1,4 +1,12 @@
-use std::{collections::HashMap, fmt::Debug, net::SocketAddr};
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU32, AtomicUsize, Ordering},
+        Arc,
+    },
+};
 
 use axum::extract::ws::Message;
 use axum_prometheus::metrics::gauge;
@@ -13,16 +21,59 @@ use uuid::Uuid;
 use yrs::{
     encoding::{read::Cursor, write::Write},
     sync::{
-        protocol::{MSG_SYNC, MSG_SYNC_UPDATE},
+        protocol::{MSG_SYNC, MSG_SYNC_STEP_1, MSG_SYNC_STEP_2, MSG_SYNC_UPDATE},
         Awareness, DefaultProtocol, MessageReader, Protocol,
     },
     updates::{
-        decoder::DecoderV1,
+        decoder::{Decode, DecoderV1},
         encoder::{Encode, Encoder, EncoderV1},
     },
-    Doc,
+    Doc, ReadTxn, StateVector, Transact, Update,
 };
 
+use crate::{
+    chunking::{self, ChunkHeader},
+    handshake::{Decryptor, Encryptor, HandshakeParams},
+    persistence::Persistence,
+};
+
+/// Compact a room's append-only log into a fresh snapshot after this many
+/// document updates.
+const COMPACTION_INTERVAL: usize = 200;
+
+/// One unit of work for a room's [`persistence_worker`]. Kept as an enum
+/// (rather than dispatching the closure directly) so the worker can log
+/// which op failed without the caller needing to know that.
+enum PersistenceOp {
+    Append(Vec<u8>),
+    Snapshot(Vec<u8>),
+}
+
+/// Runs every persistence op for one room, one at a time, on the blocking
+/// pool. `append` and `snapshot`/log-truncation both touch the same log
+/// file, so dispatching them as independent `spawn_blocking` closures (as
+/// this used to do) let a snapshot's truncation race an in-flight append
+/// for a newer update and lose it for good. Funneling them through a single
+/// queue per room makes append-then-snapshot-then-truncate one ordered
+/// sequence instead, at the cost of serializing a room's durability I/O
+/// (which was already off the shared server actor, so this doesn't add any
+/// new stall there).
+async fn persistence_worker(room_id: RoomId, persistence: Arc<dyn Persistence>, mut ops: mpsc::UnboundedReceiver<PersistenceOp>) {
+    while let Some(op) = ops.recv().await {
+        let task_persistence = persistence.clone();
+        let task_room_id = room_id.clone();
+        let result = tokio::task::spawn_blocking(move || match op {
+            PersistenceOp::Append(update) => task_persistence.append(&task_room_id, &update),
+            PersistenceOp::Snapshot(state) => task_persistence.snapshot(&task_room_id, &state),
+        })
+        .await;
+
+        if let Err(e) = result {
+            tracing::error!(room = room_id, "server: persistence task panicked: {:?}", e);
+        }
+    }
+}
+
 // Websocket
 
 /// A generic wrapper around `axum::extract::ws::WebSocket`.
@@ -32,19 +83,11 @@ pub struct Connection<Sender, Receiver> {
     pub receiver: Receiver,
 }
 
-impl<Sender, Receiver> Connection<Sender, Receiver>
-where
-    Sender: Sink<Message> + Unpin,
-    Receiver: Stream<Item = Result<Message, axum::Error>> + Unpin,
-{
-    async fn new(sender: Sender, receiver: Receiver) -> Self
-    where
-        Sender: Sink<Message> + Unpin,
-        Receiver: Stream<Item = Result<Message, axum::Error>> + Unpin,
-    {
-        Self { sender, receiver }
-    }
-}
+// Room
+
+/// Identifies a document room. Rooms are created lazily on first join and
+/// torn down once the last client leaves.
+pub type RoomId = String;
 
 // Client
 
@@ -53,18 +96,24 @@ pub type ClientId = Uuid;
 #[derive(Debug)]
 pub struct ClientHandle {
     id: ClientId,
+    // Kept for future admin/observability use (e.g. listing connected
+    // clients by address); not read anywhere yet.
+    #[allow(dead_code)]
     ip: SocketAddr,
     server_sender: mpsc::Sender<FromServerMessage>,
     join: JoinHandle<()>,
+    next_msg_id: AtomicU32,
 }
 
 impl ClientHandle {
-    pub fn send(&mut self, msg: FromServerMessage) -> Result<(), mpsc::error::TrySendError<FromServerMessage>> {
-        let res = self.server_sender.try_send(msg);
-        match res {
-            Ok(_) => Ok(()),
-            Err(err) => Err(err),
+    /// Sends `payload` to this client, transparently splitting it into
+    /// framed chunks first if it's over [`chunking::DEFAULT_CHUNK_THRESHOLD`].
+    pub fn send(&mut self, payload: Vec<u8>) -> Result<(), mpsc::error::TrySendError<FromServerMessage>> {
+        let msg_id = self.next_msg_id.fetch_add(1, Ordering::Relaxed);
+        for (header, bytes) in chunking::chunk(payload, msg_id, chunking::DEFAULT_CHUNK_THRESHOLD) {
+            self.server_sender.try_send(FromServerMessage::Chunk { header, bytes })?;
         }
+        Ok(())
     }
 }
 
@@ -76,29 +125,35 @@ impl Drop for ClientHandle {
     }
 }
 
-#[derive(Debug)]
 pub struct ClientParams<Sender, Receiver> {
     pub id: ClientId,
     pub ip: SocketAddr,
+    pub room_id: RoomId,
     pub server_handle: ServerHandle,
     pub connection: Connection<Sender, Receiver>,
     pub capacity: Option<usize>,
+    /// Set when the connection completed the secret-handshake; frames are
+    /// then transparently encrypted/decrypted in the client's send/receive
+    /// loops. `None` means the handshake is disabled (unauthenticated local
+    /// use) or was never required.
+    pub encryption: Option<(Encryptor, Decryptor)>,
 }
 
 /// Iternal actor data
 pub struct ClientData<Sender, Receiver> {
     pub id: ClientId,
+    pub room_id: RoomId,
     pub server_handle: ServerHandle,
     server_receiver: mpsc::Receiver<FromServerMessage>,
-    broadcast_receiver: broadcast::Receiver<BroadcastMessage>,
     pub connection: Connection<Sender, Receiver>,
+    pub encryption: Option<(Encryptor, Decryptor)>,
 }
 
-#[tracing::instrument(name = "client", skip(params), fields(?ip = params.ip, ?id = params.id))]
+#[tracing::instrument(name = "client", skip(params), fields(?ip = params.ip, ?id = params.id, room = params.room_id))]
 pub fn spawn_client<Sender, Receiver>(params: ClientParams<Sender, Receiver>)
 where
-    Sender: Sink<Message> + Send + Sync + Unpin + 'static + Debug,
-    Receiver: Stream<Item = Result<Message, axum::Error>> + Send + Sync + Unpin + 'static + Debug,
+    Sender: Sink<Message> + Send + Unpin + 'static + Debug,
+    Receiver: Stream<Item = Result<Message, axum::Error>> + Send + Unpin + 'static + Debug,
 {
     tracing::trace!("client: spawn");
     gauge!("editor.connections.count").increment(1);
@@ -108,10 +163,11 @@ where
     // Iternal actor data
     let mut data = ClientData {
         id: params.id,
+        room_id: params.room_id,
         server_handle: params.server_handle.clone(),
         server_receiver,
-        broadcast_receiver: params.server_handle.subscribe(),
         connection: params.connection,
+        encryption: params.encryption,
     };
 
     // This spawns the new task.
@@ -121,18 +177,51 @@ where
             Ok(handle) => handle,
             Err(_) => return,
         };
-        data.server_handle.send(ToServerMessage::Join(handle)).await;
+
+        // The room's broadcast channel only exists once the room itself
+        // exists, so we ask the server to create/find the room and hand
+        // us a subscription as part of joining, instead of subscribing
+        // up front like in the single-document version.
+        let (join_reply_sender, join_reply_receiver) = oneshot::channel();
+        data.server_handle
+            .send(ToServerMessage::Join(data.room_id.clone(), handle, join_reply_sender))
+            .await;
+        let mut broadcast_receiver = match join_reply_receiver.await {
+            Ok(receiver) => receiver,
+            Err(_) => return,
+        };
 
         // Websocket connection loop
+        let (mut encryptor, mut decryptor) = match data.encryption {
+            Some((encryptor, decryptor)) => (Some(encryptor), Some(decryptor)),
+            None => (None, None),
+        };
+
         let mut connection_receiver = data.connection.receiver;
         let mut server_handle = data.server_handle.clone();
+        let room_id = data.room_id.clone();
+        let id = data.id;
         let mut connection_receive_loop = tokio::spawn(async move {
             tracing::debug!("client: run ws receive loop");
             while let Some(result) = connection_receiver.next().await {
                 match result {
                     Ok(Message::Binary(input)) => {
                         tracing::debug!("client: receive ws binary message");
-                        _ = server_handle.send(ToServerMessage::Message(data.id, input)).await;
+
+                        let input = match &mut decryptor {
+                            Some(decryptor) => match decryptor.decrypt(&input) {
+                                Some(plaintext) => plaintext,
+                                None => {
+                                    tracing::error!("client: failed to decrypt frame, dropping connection");
+                                    break;
+                                }
+                            },
+                            None => input,
+                        };
+
+                        _ = server_handle
+                            .send(ToServerMessage::Message(room_id.clone(), id, input))
+                            .await;
                     }
                     Ok(message) => {
                         tracing::debug!("client: receive other message: {message:?}");
@@ -145,29 +234,57 @@ where
             }
         });
         let mut connection_sender = data.connection.sender;
+        let mut resync_server_handle = data.server_handle.clone();
+        let resync_room_id = data.room_id.clone();
+        let resync_id = data.id;
         let mut connection_send_loop = tokio::spawn(async move {
             tracing::debug!("client: run ws send loop");
-            while let Ok(BroadcastMessage::Binary { payload }) = data.broadcast_receiver.recv().await {
-                if let Err(e) = connection_sender.send(Message::Binary(payload)).await {
-                    tracing::error!("client: ws send error");
-                    break;
-                }
-            }
-
-            while let Some(message) = data.server_receiver.recv().await {
-                match message {
-                    FromServerMessage::Binary(data) => {
-                        tracing::debug!("client: send ws binary message");
-
-                        if let Err(e) = connection_sender.send(Message::Binary(data)).await {
-                            tracing::error!("client: ws send error");
-                            break;
+            // Broadcast updates and direct replies (e.g. the resync below)
+            // are raced against each other so a lag on one never starves
+            // the other. Each chunk is written as its own `Message::Binary`
+            // as soon as it's picked up here, instead of being reassembled
+            // first: that's what keeps one oversized sync from blocking the
+            // loop for the time it takes to write a single giant frame, and
+            // what lets an unrelated small chunk (e.g. an awareness update)
+            // actually get interleaved onto the wire instead of queuing
+            // behind it. The client on the other end reassembles the
+            // chunks of each `msg_id` back into one payload, the same way
+            // `chunking::Reassembler` does here for inbound use.
+            loop {
+                let frame = tokio::select! {
+                    broadcast = broadcast_receiver.recv() => {
+                        match broadcast {
+                            Ok(BroadcastMessage::Chunk { header, bytes }) => chunking::encode_frame(header, bytes),
+                            Err(broadcast::error::RecvError::Lagged(n)) => {
+                                tracing::warn!("client: lagged behind broadcast by {n} messages, resyncing");
+                                resync_server_handle
+                                    .send(ToServerMessage::Resync(resync_room_id.clone(), resync_id))
+                                    .await;
+                                continue;
+                            }
+                            Err(broadcast::error::RecvError::Closed) => break,
                         }
                     }
+                    message = data.server_receiver.recv() => {
+                        match message {
+                            Some(FromServerMessage::Chunk { header, bytes }) => chunking::encode_frame(header, bytes),
+                            None => break,
+                        }
+                    }
+                };
+
+                tracing::debug!("client: send ws binary message");
+                let frame = match &mut encryptor {
+                    Some(encryptor) => encryptor.encrypt(&frame),
+                    None => frame,
                 };
+                if connection_sender.send(Message::Binary(frame)).await.is_err() {
+                    tracing::error!("client: ws send error");
+                    break;
+                }
             }
         });
-        _ = tokio::select! {
+        tokio::select! {
             _ = &mut connection_send_loop => {
                 tracing::trace!("client: abort connection_receive_loop");
                 connection_receive_loop.abort()
@@ -179,7 +296,9 @@ where
         };
 
         tracing::debug!("client: leave");
-        data.server_handle.send(ToServerMessage::Leave(data.id)).await;
+        data.server_handle
+            .send(ToServerMessage::Leave(data.room_id.clone(), data.id))
+            .await;
     });
 
     // Then we create a ClientHandle to this new task, and use the oneshot
@@ -189,6 +308,7 @@ where
         ip: params.ip,
         server_sender,
         join: kill_handle,
+        next_msg_id: AtomicU32::new(0),
     };
 
     let _ = handle_sender.send(handle);
@@ -199,7 +319,10 @@ where
 #[derive(Debug, Clone)]
 pub struct ServerHandle {
     sender: mpsc::Sender<ToServerMessage>,
-    bsender: broadcast::Sender<BroadcastMessage>,
+    /// `None` unless `ServerParams::handshake` was set: lets the websocket
+    /// route decide whether to run the secret-handshake before spawning a
+    /// client at all.
+    pub handshake: Option<Arc<HandshakeParams>>,
 }
 
 impl ServerHandle {
@@ -208,112 +331,257 @@ impl ServerHandle {
             panic!("Main loop has shut down.");
         }
     }
-    pub fn subscribe(&self) -> broadcast::Receiver<BroadcastMessage> {
-        self.bsender.subscribe()
-    }
 }
 
 pub struct ServerParams {
     pub capacity: Option<usize>,
+    pub persistence: Option<Arc<dyn Persistence>>,
+    pub handshake: Option<Arc<HandshakeParams>>,
 }
 
-#[derive(Default, Debug)]
-struct ServerData {
-    clients: HashMap<ClientId, ClientHandle>,
+/// Everything the server keeps for a single document room: its own `Doc`,
+/// `Awareness`, subscriptions and client set, all independent from any
+/// other room.
+struct RoomState {
     awareness: Awareness,
+    clients: HashMap<ClientId, ClientHandle>,
+    bsender: broadcast::Sender<BroadcastMessage>,
+    // Kept alive for as long as the room exists; dropping them unsubscribes
+    // from doc/awareness updates when the room is torn down.
+    _doc_sub: yrs::Subscription,
+    _awareness_sub: yrs::Subscription,
+}
+
+impl RoomState {
+    /// `loaded` is whatever `Persistence::load` already returned for
+    /// `room_id`, fetched by the caller (on the blocking pool, since it's
+    /// filesystem I/O) before constructing the room.
+    fn new(
+        room_id: RoomId,
+        capacity: Option<usize>,
+        persistence: Option<Arc<dyn Persistence>>,
+        loaded: Option<Vec<u8>>,
+    ) -> Self {
+        let doc = Doc::new();
+
+        if let Some(saved) = loaded {
+            match Update::decode_v1(&saved) {
+                Ok(update) => {
+                    if let Err(e) = doc.transact_mut().apply_update(update) {
+                        tracing::error!(room = room_id, "server: failed to restore persisted state: {:?}", e);
+                    }
+                }
+                Err(e) => tracing::error!(room = room_id, "server: corrupt persisted state: {:?}", e),
+            }
+        }
+
+        let awareness = Awareness::new(doc);
+        let (bsender, _) = broadcast::channel(capacity.unwrap_or(100));
+
+        let doc_broadcast_sender = bsender.clone();
+        let awareness_broadcast_sender = bsender.clone();
+        let update_count = AtomicUsize::new(0);
+        let doc_msg_id_counter = Arc::new(AtomicU32::new(0));
+        let awareness_msg_id_counter = doc_msg_id_counter.clone();
+
+        // A single ordered queue per room, instead of each update/snapshot
+        // being its own independent `spawn_blocking` call: see
+        // `persistence_worker` for why that ordering matters.
+        let persistence_sender = persistence.map(|persistence| {
+            let (sender, receiver) = mpsc::unbounded_channel();
+            tokio::spawn(persistence_worker(room_id.clone(), persistence, receiver));
+            sender
+        });
+
+        let doc_room_id = room_id.clone();
+        let awareness_room_id = room_id;
+
+        let mut awareness = awareness;
+        let doc_sub = awareness
+            .doc_mut()
+            .observe_update_v1(move |txn, u| {
+                let mut encoder = EncoderV1::new();
+                encoder.write_var(MSG_SYNC);
+                encoder.write_var(MSG_SYNC_UPDATE);
+                encoder.write_buf(&u.update);
+                let payload = encoder.to_vec();
+
+                tracing::debug!(room = doc_room_id, "server: broadcast docs");
+                broadcast_chunked(&doc_broadcast_sender, payload, &doc_msg_id_counter);
+
+                // Persistence does blocking filesystem I/O, but this
+                // observer runs inline on the single server actor task that
+                // every room shares — blocking here would stall `Join`/
+                // `Leave`/`Message` handling for every other room too, so
+                // the actual I/O is pushed onto `persistence_worker`. It has
+                // to go through that one ordered queue rather than its own
+                // `spawn_blocking` call: a snapshot can only safely truncate
+                // the log once every append for an older update has already
+                // landed, and unsynchronized blocking-pool tasks can't
+                // promise that.
+                if let Some(sender) = &persistence_sender {
+                    let _ = sender.send(PersistenceOp::Append(u.update.clone()));
+
+                    let count = update_count.fetch_add(1, Ordering::Relaxed) + 1;
+                    if count.is_multiple_of(COMPACTION_INTERVAL) {
+                        tracing::debug!(room = doc_room_id, "server: compacting persisted log");
+                        let state = txn.encode_state_as_update_v1(&StateVector::default());
+                        let _ = sender.send(PersistenceOp::Snapshot(state));
+                    }
+                }
+            })
+            .unwrap();
+
+        let awareness_sub = awareness.on_update(move |awareness, e, _origin| {
+            let added = e.added();
+            let updated = e.updated();
+            let removed = e.removed();
+            let mut changed = Vec::with_capacity(added.len() + updated.len() + removed.len());
+            changed.extend_from_slice(added);
+            changed.extend_from_slice(updated);
+            changed.extend_from_slice(removed);
+
+            if let Ok(u) = awareness.update_with_clients(changed) {
+                let payload = yrs::sync::Message::Awareness(u).encode_v1();
+
+                tracing::debug!(room = awareness_room_id, "server: broadcast awareness");
+                broadcast_chunked(&awareness_broadcast_sender, payload, &awareness_msg_id_counter);
+            }
+        });
+
+        Self {
+            awareness,
+            clients: HashMap::default(),
+            bsender,
+            _doc_sub: doc_sub,
+            _awareness_sub: awareness_sub,
+        }
+    }
+}
+
+#[derive(Default)]
+struct ServerData {
+    rooms: HashMap<RoomId, RoomState>,
 }
 
 pub enum ToServerMessage {
-    Join(ClientHandle),
-    Leave(ClientId),
-    Message(ClientId, Vec<u8>),
+    Join(RoomId, ClientHandle, oneshot::Sender<broadcast::Receiver<BroadcastMessage>>),
+    Leave(RoomId, ClientId),
+    Message(RoomId, ClientId, Vec<u8>),
+    /// A client fell behind its broadcast subscription and needs the
+    /// authoritative document state pushed to it directly, instead of
+    /// staying silently out of sync.
+    Resync(RoomId, ClientId),
+    /// Graceful-shutdown hook; no caller wires this up yet (there's no
+    /// signal handler that sends it), but `spawn_server`'s actor already
+    /// handles it.
+    #[allow(dead_code)]
     Stop,
 }
 
+/// Encodes the room's current document state as a `SyncStep1`/`SyncStep2`
+/// pair, the same handshake a freshly joining client would receive, so a
+/// client that missed broadcast updates can catch back up.
+fn encode_full_resync(awareness: &Awareness) -> Vec<u8> {
+    let txn = awareness.doc().transact();
+    let sv = txn.state_vector();
+    let update = txn.encode_state_as_update_v1(&StateVector::default());
+
+    let mut encoder = EncoderV1::new();
+    encoder.write_var(MSG_SYNC);
+    encoder.write_var(MSG_SYNC_STEP_1);
+    // `SyncMessage::SyncStep1`'s own `Encode` impl writes the state vector
+    // through `write_buf` (length-prefixed), not a bare `sv.encode(..)` —
+    // matching that here is what lets `MessageReader` decode this frame.
+    encoder.write_buf(sv.encode_v1());
+
+    encoder.write_var(MSG_SYNC);
+    encoder.write_var(MSG_SYNC_STEP_2);
+    encoder.write_buf(&update);
+
+    encoder.to_vec()
+}
+
 #[derive(Debug, Clone)]
 pub enum BroadcastMessage {
-    Binary { payload: Vec<u8> },
+    Chunk { header: ChunkHeader, bytes: Vec<u8> },
 }
 
 #[derive(Debug, Clone)]
 pub enum FromServerMessage {
-    Binary(Vec<u8>),
+    Chunk { header: ChunkHeader, bytes: Vec<u8> },
+}
+
+/// Chunks `payload` and sends each piece over `sender`, tagging them with a
+/// fresh `msg_id` drawn from `msg_id_counter` so they can't be confused
+/// with any other message's chunks in flight on the same broadcast.
+fn broadcast_chunked(sender: &broadcast::Sender<BroadcastMessage>, payload: Vec<u8>, msg_id_counter: &AtomicU32) {
+    let msg_id = msg_id_counter.fetch_add(1, Ordering::Relaxed);
+    for (header, bytes) in chunking::chunk(payload, msg_id, chunking::DEFAULT_CHUNK_THRESHOLD) {
+        let _ = sender.send(BroadcastMessage::Chunk { header, bytes });
+    }
 }
 
 #[tracing::instrument(name="server", skip(params), fields(capacity = params.capacity))]
 pub fn spawn_server(params: ServerParams) -> (ServerHandle, JoinHandle<()>) {
     tracing::debug!("server: spawn");
 
-    let (bsender, _) = broadcast::channel(params.capacity.unwrap_or(100));
     let (sender, mut receiver) = mpsc::channel(params.capacity.unwrap_or(100));
-
-    let doc_broadcast_sender = bsender.clone();
-    let awareness_broadcast_sender = bsender.clone();
-    let handle = ServerHandle { sender, bsender };
+    let capacity = params.capacity;
+    let persistence = params.persistence;
+    let handle = ServerHandle { sender, handshake: params.handshake };
 
     let join = tokio::spawn(
         async move {
-            let doc = Doc::new();
-
-            let awareness = Awareness::new(doc);
             let protocol = DefaultProtocol;
-
-            let mut data = ServerData {
-                clients: HashMap::default(),
-                awareness,
-            };
-
-            let (doc_sub, awareness_sub) = {
-                let doc_sub = data
-                    .awareness
-                    .doc_mut()
-                    .observe_update_v1(move |_txn, u| {
-                        let mut encoder = EncoderV1::new();
-                        encoder.write_var(MSG_SYNC);
-                        encoder.write_var(MSG_SYNC_UPDATE);
-                        encoder.write_buf(&u.update);
-                        let payload = encoder.to_vec();
-
-                        tracing::debug!("server: broadcast docs");
-                        let res = doc_broadcast_sender.send(BroadcastMessage::Binary { payload });
-                    })
-                    .unwrap();
-
-                let awareness_sub = data.awareness.on_update(move |awareness, e, origin| {
-                    let added = e.added();
-                    let updated = e.updated();
-                    let removed = e.removed();
-                    let mut changed = Vec::with_capacity(added.len() + updated.len() + removed.len());
-                    changed.extend_from_slice(added);
-                    changed.extend_from_slice(updated);
-                    changed.extend_from_slice(removed);
-
-                    if let Ok(u) = awareness.update_with_clients(changed) {
-                        let payload = yrs::sync::Message::Awareness(u).encode_v1();
-
-                        tracing::debug!("server: broadcast awareness");
-                        let res = awareness_broadcast_sender.send(BroadcastMessage::Binary { payload });
-                    }
-                });
-                (doc_sub, awareness_sub)
-            };
+            let mut data = ServerData::default();
 
             tracing::debug!("server: run loop");
             while let Some(message) = receiver.recv().in_current_span().await {
                 match message {
-                    ToServerMessage::Join(mut client_handle) => {
-                        tracing::debug!("server: new client {:?}", client_handle.id);
+                    ToServerMessage::Join(room_id, mut client_handle, reply) => {
+                        tracing::debug!(room = room_id, "server: new client {:?}", client_handle.id);
+
+                        let room = if data.rooms.contains_key(&room_id) {
+                            data.rooms.get_mut(&room_id).unwrap()
+                        } else {
+                            // `load` does blocking filesystem I/O; run it on the
+                            // blocking pool and await it here rather than inline,
+                            // so a slow/first load doesn't stall every other
+                            // room's Join/Leave/Message handling on this actor.
+                            let loaded = match &persistence {
+                                Some(persistence) => {
+                                    let load_persistence = persistence.clone();
+                                    let load_room_id = room_id.clone();
+                                    tokio::task::spawn_blocking(move || load_persistence.load(&load_room_id))
+                                        .await
+                                        .unwrap_or(None)
+                                }
+                                None => None,
+                            };
+
+                            data.rooms.entry(room_id.clone()).or_insert_with(|| {
+                                RoomState::new(room_id.clone(), capacity, persistence.clone(), loaded)
+                            })
+                        };
+
+                        let _ = reply.send(room.bsender.subscribe());
 
                         let encoder = EncoderV1::new();
                         let payload = encoder.to_vec();
                         if !payload.is_empty() {
-                            _ = client_handle.send(FromServerMessage::Binary(payload));
+                            _ = client_handle.send(payload);
                         }
 
-                        data.clients.insert(client_handle.id, client_handle);
+                        room.clients.insert(client_handle.id, client_handle);
                     }
-                    ToServerMessage::Message(from_id, input) => {
-                        tracing::debug!("server: got message from: {:?}", from_id);
+                    ToServerMessage::Message(room_id, from_id, input) => {
+                        tracing::debug!(room = room_id, "server: got message from: {:?}", from_id);
+
+                        let Some(room) = data.rooms.get_mut(&room_id) else {
+                            tracing::warn!(room = room_id, "server: message for unknown room");
+                            continue;
+                        };
 
                         {
                             let mut decoder = DecoderV1::new(Cursor::new(&input));
@@ -322,20 +590,38 @@ pub fn spawn_server(params: ServerParams) -> (ServerHandle, JoinHandle<()>) {
                             tracing::trace!("server: input messages: {:?}", dbg_msgs);
                         }
 
-                        let replies = protocol.handle(&data.awareness, &input);
-                        let client_handle = data.clients.get_mut(&from_id).unwrap();
+                        let replies = protocol.handle(&room.awareness, &input);
+                        let client_handle = room.clients.get_mut(&from_id).unwrap();
 
                         if let Ok(replies) = replies {
-                            tracing::debug!("server: reply to {:?}", from_id);
+                            tracing::debug!(room = room_id, "server: reply to {:?}", from_id);
                             for reply in replies {
                                 tracing::trace!("server: output message: {:?}", reply);
-                                _ = client_handle.send(FromServerMessage::Binary(reply.encode_v1()));
+                                _ = client_handle.send(reply.encode_v1());
                             }
                         }
                     }
-                    ToServerMessage::Leave(id) => {
-                        tracing::debug!("server: remove client: {:?}", id);
-                        data.clients.remove(&id);
+                    ToServerMessage::Resync(room_id, id) => {
+                        tracing::debug!(room = room_id, "server: resync client: {:?}", id);
+
+                        if let Some(room) = data.rooms.get_mut(&room_id) {
+                            let payload = encode_full_resync(&room.awareness);
+                            if let Some(client_handle) = room.clients.get_mut(&id) {
+                                _ = client_handle.send(payload);
+                            }
+                        }
+                    }
+                    ToServerMessage::Leave(room_id, id) => {
+                        tracing::debug!(room = room_id, "server: remove client: {:?}", id);
+
+                        if let Some(room) = data.rooms.get_mut(&room_id) {
+                            room.clients.remove(&id);
+
+                            if room.clients.is_empty() {
+                                tracing::debug!(room = room_id, "server: last client left, dropping room");
+                                data.rooms.remove(&room_id);
+                            }
+                        }
                     }
                     ToServerMessage::Stop => {
                         break;
@@ -350,4 +636,152 @@ pub fn spawn_server(params: ServerParams) -> (ServerHandle, JoinHandle<()>) {
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use yrs::{
+        encoding::read::Cursor,
+        sync::{Message as SyncProtoMessage, SyncMessage},
+        updates::decoder::DecoderV1,
+        GetString, Text,
+    };
+
+    use super::*;
+
+    /// Joins a fake client to `room_id`, returning its id alongside the two
+    /// channels the server actually talks to it over: the direct-reply
+    /// channel (`FromServerMessage`, used for sync replies and resyncs) and
+    /// the room's broadcast subscription (`BroadcastMessage`, used for
+    /// updates from other clients). Tests only have access to the private
+    /// `ClientHandle` fields because this module nests inside `editor`.
+    async fn join_room(
+        server: &mut ServerHandle,
+        room_id: &str,
+    ) -> (ClientId, mpsc::Receiver<FromServerMessage>, broadcast::Receiver<BroadcastMessage>) {
+        let id = Uuid::now_v7();
+        let (server_sender, server_receiver) = mpsc::channel(16);
+        let client_handle = ClientHandle {
+            id,
+            ip: "127.0.0.1:0".parse().unwrap(),
+            server_sender,
+            join: tokio::spawn(async {}),
+            next_msg_id: AtomicU32::new(0),
+        };
+
+        let (reply_sender, reply_receiver) = oneshot::channel();
+        server.send(ToServerMessage::Join(room_id.to_string(), client_handle, reply_sender)).await;
+        let broadcast_receiver = reply_receiver.await.expect("server should reply to a Join");
+
+        (id, server_receiver, broadcast_receiver)
+    }
+
+    /// Wraps `text` as a single-message `yrs` sync-protocol `Update` frame,
+    /// the same shape `ToServerMessage::Message` expects as input.
+    fn encode_update_message(text: &str) -> Vec<u8> {
+        let doc = Doc::new();
+        let content = doc.get_or_insert_text("content");
+        content.insert(&mut doc.transact_mut(), 0, text);
+        let update = doc.transact().encode_state_as_update_v1(&StateVector::default());
+        SyncProtoMessage::Sync(SyncMessage::Update(update)).encode_v1()
+    }
+
+    /// Reads the `content` text out of the first `SyncStep2` message found
+    /// in `frame`, the reply shape produced by both a plain sync exchange
+    /// and `encode_full_resync`.
+    fn decode_doc_text(frame: &[u8]) -> String {
+        let mut decoder = DecoderV1::new(Cursor::new(frame));
+        for message in MessageReader::new(&mut decoder) {
+            if let Ok(SyncProtoMessage::Sync(SyncMessage::SyncStep2(update))) = message {
+                let doc = Doc::new();
+                doc.transact_mut().apply_update(Update::decode_v1(&update).unwrap()).unwrap();
+                let content = doc.get_or_insert_text("content");
+                let txn = doc.transact();
+                return content.get_string(&txn);
+            }
+        }
+        panic!("frame did not contain a SyncStep2 message");
+    }
+
+    #[tokio::test]
+    async fn two_rooms_stay_isolated() {
+        let (mut server, _join) = spawn_server(ServerParams { capacity: None, persistence: None, handshake: None });
+
+        let (client_a, _from_a, mut broadcast_a) = join_room(&mut server, "room-a").await;
+        let (_client_b, _from_b, mut broadcast_b) = join_room(&mut server, "room-b").await;
+
+        server.send(ToServerMessage::Message("room-a".to_string(), client_a, encode_update_message("hello"))).await;
+
+        assert!(
+            matches!(broadcast_a.recv().await, Ok(BroadcastMessage::Chunk { .. })),
+            "room-a's own subscriber should see its update"
+        );
+        assert!(
+            matches!(broadcast_b.try_recv(), Err(broadcast::error::TryRecvError::Empty)),
+            "room-b should never see an update broadcast in room-a"
+        );
+    }
+
+    #[tokio::test]
+    async fn room_is_torn_down_and_recreated_fresh_after_the_last_client_leaves() {
+        let (mut server, _join) = spawn_server(ServerParams { capacity: None, persistence: None, handshake: None });
+
+        let (first_client, _from_first, _broadcast_first) = join_room(&mut server, "room-x").await;
+        server
+            .send(ToServerMessage::Message("room-x".to_string(), first_client, encode_update_message("first")))
+            .await;
+        server.send(ToServerMessage::Leave("room-x".to_string(), first_client)).await;
+
+        let (second_client, mut from_second, _broadcast_second) = join_room(&mut server, "room-x").await;
+        server
+            .send(ToServerMessage::Message(
+                "room-x".to_string(),
+                second_client,
+                SyncProtoMessage::Sync(SyncMessage::SyncStep1(StateVector::default())).encode_v1(),
+            ))
+            .await;
+
+        let FromServerMessage::Chunk { bytes, .. } =
+            from_second.recv().await.expect("server should reply to SyncStep1 with a SyncStep2");
+        assert_eq!(
+            decode_doc_text(&bytes),
+            "",
+            "a room recreated after the last client left should start empty, not carry over the torn-down room's state"
+        );
+    }
+
+    #[tokio::test]
+    async fn lagged_client_gets_a_resync_instead_of_silently_stalling() {
+        // A tiny broadcast capacity makes it easy to outrun a subscriber
+        // without consuming it, the same situation `connection_send_loop`
+        // detects as `RecvError::Lagged` and reacts to by sending `Resync`.
+        let (mut server, _join) = spawn_server(ServerParams { capacity: Some(2), persistence: None, handshake: None });
+
+        let (client, mut from_client, mut broadcast) = join_room(&mut server, "room-lag").await;
+
+        // One doc growing across all 8 messages, each diffed against the
+        // previous state vector, so they land as successive edits rather
+        // than 8 concurrent inserts that a CRDT merge could reorder —
+        // matching what a real client's incremental edits look like.
+        let doc = Doc::new();
+        let content = doc.get_or_insert_text("content");
+        let mut prev_sv = StateVector::default();
+        for i in 0..8 {
+            content.push(&mut doc.transact_mut(), &format!("{i}"));
+            let update = doc.transact().encode_state_as_update_v1(&prev_sv);
+            prev_sv = doc.transact().state_vector();
+            let message = SyncProtoMessage::Sync(SyncMessage::Update(update)).encode_v1();
+            server.send(ToServerMessage::Message("room-lag".to_string(), client, message)).await;
+        }
+
+        assert!(
+            matches!(broadcast.recv().await, Err(broadcast::error::RecvError::Lagged(_))),
+            "the subscriber should have fallen behind once updates outran its capacity"
+        );
+
+        // This is exactly what `connection_send_loop` does upon observing
+        // `RecvError::Lagged`, instead of leaving the client silently stale.
+        server.send(ToServerMessage::Resync("room-lag".to_string(), client)).await;
+
+        let FromServerMessage::Chunk { bytes, .. } =
+            from_client.recv().await.expect("server should push a resync payload");
+        assert_eq!(decode_doc_text(&bytes), "01234567", "resync should reflect the room's latest state");
+    }
+}
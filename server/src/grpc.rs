@@ -0,0 +1,197 @@
+use std::{
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use axum::extract::ws::Message;
+use futures::{Sink, Stream, StreamExt};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_util::sync::PollSender;
+use tonic::{Request, Response, Status, Streaming};
+use uuid::Uuid;
+
+use crate::{
+    editor::{spawn_client, ClientParams, Connection, RoomId, ServerHandle},
+    handshake::server_handshake,
+};
+
+tonic::include_proto!("editor");
+
+use editor_server::Editor;
+
+/// Metadata key a native client sets to pick which room it's joining,
+/// since gRPC has no URL path the way the websocket route does.
+const ROOM_ID_METADATA_KEY: &str = "room-id";
+
+/// Bidirectional-streaming gRPC transport for native clients that don't
+/// want a websocket layer. It builds the same `Connection`/`ClientParams`
+/// abstraction the websocket route does, by adapting the tonic in/out
+/// streams into the `Sink<Message>`/`Stream<Item = Result<Message, _>>`
+/// bounds `spawn_client` requires, then reuses `ServerHandle` unchanged.
+#[derive(Clone)]
+pub struct EditorService {
+    server_handle: ServerHandle,
+}
+
+impl EditorService {
+    pub fn new(server_handle: ServerHandle) -> Self {
+        Self { server_handle }
+    }
+}
+
+#[tonic::async_trait]
+impl Editor for EditorService {
+    type StreamStream = Pin<Box<dyn Stream<Item = Result<SyncFrame, Status>> + Send + 'static>>;
+
+    async fn stream(&self, request: Request<Streaming<SyncFrame>>) -> Result<Response<Self::StreamStream>, Status> {
+        let room_id = room_id_from_metadata(&request)?;
+        let addr = request.remote_addr().unwrap_or_else(|| SocketAddr::from(([0, 0, 0, 0], 0)));
+
+        let receiver = request.into_inner().map(|result| {
+            result
+                .map(|frame| Message::Binary(frame.payload))
+                .map_err(axum::Error::new)
+        });
+
+        let (sender, outbound) = mpsc::channel(100);
+        let mut connection = Connection { sender: GrpcSink::new(sender), receiver };
+
+        // Gate gRPC behind the same secret-handshake the websocket route
+        // requires: this transport has no TLS/credential config of its own,
+        // so skipping this would let anyone who can reach the gRPC port
+        // bypass the network key entirely.
+        let encryption = match &self.server_handle.handshake {
+            Some(params) => match server_handshake(&mut connection, params).await {
+                Some(keys) => Some(keys),
+                None => {
+                    tracing::warn!("handshake: rejecting grpc connection from {addr} that failed the handshake");
+                    return Err(Status::unauthenticated("handshake failed"));
+                }
+            },
+            None => None,
+        };
+
+        spawn_client(ClientParams {
+            id: Uuid::now_v7(),
+            ip: addr,
+            room_id,
+            server_handle: self.server_handle.clone(),
+            connection,
+            capacity: None,
+            encryption,
+        });
+
+        Ok(Response::new(Box::pin(ReceiverStream::new(outbound)) as Self::StreamStream))
+    }
+}
+
+// `Status` is the error type tonic handlers are expected to return; boxing
+// it here would just push the cost onto every caller instead.
+#[allow(clippy::result_large_err)]
+fn room_id_from_metadata<T>(request: &Request<T>) -> Result<RoomId, Status> {
+    let room_id = request
+        .metadata()
+        .get(ROOM_ID_METADATA_KEY)
+        .ok_or_else(|| Status::invalid_argument(format!("missing `{ROOM_ID_METADATA_KEY}` metadata")))?;
+
+    room_id
+        .to_str()
+        .map(str::to_owned)
+        .map_err(|_| Status::invalid_argument(format!("`{ROOM_ID_METADATA_KEY}` must be ASCII")))
+}
+
+/// Adapts an `mpsc::Sender<Result<SyncFrame, Status>>` — the half of the
+/// outbound gRPC stream tonic reads from — into the `Sink<Message>` that
+/// `Connection`/`spawn_client` expect, so the same client send loop that
+/// drives a websocket can drive a gRPC stream too.
+///
+/// Wraps the sender in a `PollSender` so `poll_ready` actually reports the
+/// channel's capacity instead of always claiming readiness: a burst larger
+/// than the 100-slot buffer (easily reached by a single large initial sync
+/// under chunk0-6's chunk threshold) should make the send loop wait, not
+/// tear down the connection.
+#[derive(Debug)]
+struct GrpcSink {
+    sender: PollSender<Result<SyncFrame, Status>>,
+}
+
+impl GrpcSink {
+    fn new(sender: mpsc::Sender<Result<SyncFrame, Status>>) -> Self {
+        Self { sender: PollSender::new(sender) }
+    }
+}
+
+impl Sink<Message> for GrpcSink {
+    type Error = axum::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.sender.poll_reserve(cx).map_err(axum::Error::new)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, item: Message) -> Result<(), Self::Error> {
+        let payload = match item {
+            Message::Binary(payload) => payload,
+            _ => return Ok(()),
+        };
+
+        self.sender.send_item(Ok(SyncFrame { payload })).map_err(axum::Error::new)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.sender.close();
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::SinkExt;
+    use tonic::{metadata::MetadataValue, Code};
+
+    use super::*;
+
+    #[test]
+    fn room_id_from_metadata_requires_the_header() {
+        let request = Request::new(());
+        let err = room_id_from_metadata(&request).expect_err("missing room-id metadata should be rejected");
+        assert_eq!(err.code(), Code::InvalidArgument);
+    }
+
+    #[test]
+    fn room_id_from_metadata_reads_the_header() {
+        let mut request = Request::new(());
+        request.metadata_mut().insert(ROOM_ID_METADATA_KEY, MetadataValue::from_static("room-42"));
+        assert_eq!(room_id_from_metadata(&request).unwrap(), "room-42");
+    }
+
+    #[tokio::test]
+    async fn grpc_sink_forwards_binary_messages_and_drops_everything_else() {
+        let (tx, mut rx) = mpsc::channel(4);
+        let mut sink = GrpcSink::new(tx);
+
+        sink.send(Message::Binary(vec![1, 2, 3])).await.unwrap();
+        // Not a transport frame the sync protocol produces; should be
+        // silently dropped rather than forwarded or erroring the stream.
+        sink.send(Message::Ping(vec![9])).await.unwrap();
+        sink.send(Message::Binary(vec![4, 5])).await.unwrap();
+
+        assert_eq!(rx.recv().await.unwrap().unwrap().payload, vec![1, 2, 3]);
+        assert_eq!(rx.recv().await.unwrap().unwrap().payload, vec![4, 5]);
+    }
+
+    #[tokio::test]
+    async fn grpc_sink_close_closes_the_outbound_channel() {
+        let (tx, mut rx) = mpsc::channel(4);
+        let mut sink = GrpcSink::new(tx);
+
+        sink.close().await.unwrap();
+
+        assert!(rx.recv().await.is_none(), "closing the sink should close the channel tonic streams from");
+    }
+}
@@ -0,0 +1,268 @@
+use axum::extract::ws::Message;
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    ChaCha20Poly1305, Nonce,
+};
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+use subtle::ConstantTimeEq;
+
+use crate::editor::Connection;
+
+pub const NETWORK_KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+
+/// Shared, operator-configured secret that gates access to the server,
+/// mirroring the pre-shared network key used by netapp/kuska-handshake's
+/// secret-handshake: a client that doesn't know it can't complete the
+/// challenge-response below, so it never reaches `ToServerMessage::Join`.
+#[derive(Clone)]
+pub struct HandshakeParams {
+    network_key: [u8; NETWORK_KEY_LEN],
+}
+
+impl std::fmt::Debug for HandshakeParams {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("HandshakeParams").field("network_key", &"<redacted>").finish()
+    }
+}
+
+impl HandshakeParams {
+    pub fn new(network_key: [u8; NETWORK_KEY_LEN]) -> Self {
+        Self { network_key }
+    }
+}
+
+/// Encrypts outgoing frames with the session key derived for this
+/// connection's server->client direction.
+pub struct Encryptor {
+    cipher: ChaCha20Poly1305,
+    counter: u64,
+}
+
+/// Decrypts incoming frames with the session key derived for this
+/// connection's client->server direction.
+pub struct Decryptor {
+    cipher: ChaCha20Poly1305,
+    counter: u64,
+}
+
+fn nonce_for(counter: u64) -> Nonce {
+    let mut bytes = [0u8; NONCE_LEN];
+    bytes[..8].copy_from_slice(&counter.to_le_bytes());
+    Nonce::clone_from_slice(&bytes)
+}
+
+impl Encryptor {
+    /// Encrypts a single frame. Frames must be decrypted in the same order
+    /// they were encrypted, since the nonce is just a counter.
+    pub fn encrypt(&mut self, plaintext: &[u8]) -> Vec<u8> {
+        let nonce = nonce_for(self.counter);
+        self.counter += 1;
+        self.cipher.encrypt(&nonce, plaintext).expect("encryption with a fresh session key cannot fail")
+    }
+}
+
+impl Decryptor {
+    pub fn decrypt(&mut self, ciphertext: &[u8]) -> Option<Vec<u8>> {
+        let nonce = nonce_for(self.counter);
+        self.counter += 1;
+        self.cipher.decrypt(&nonce, ciphertext).ok()
+    }
+}
+
+fn derive_key(session_key: &[u8], label: &[u8]) -> ChaCha20Poly1305 {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(session_key).expect("HMAC accepts any key length");
+    mac.update(label);
+    let key = mac.finalize().into_bytes();
+    ChaCha20Poly1305::new_from_slice(&key).expect("derived key is the right length for ChaCha20Poly1305")
+}
+
+fn split_session_key(session_key: &[u8], server_side: bool) -> (Encryptor, Decryptor) {
+    let c2s = derive_key(session_key, b"simple-y-crdt-editor-c2s");
+    let s2c = derive_key(session_key, b"simple-y-crdt-editor-s2c");
+
+    let (encrypt_cipher, decrypt_cipher) = if server_side { (s2c, c2s) } else { (c2s, s2c) };
+
+    (
+        Encryptor { cipher: encrypt_cipher, counter: 0 },
+        Decryptor { cipher: decrypt_cipher, counter: 0 },
+    )
+}
+
+/// Runs the server side of the secret-handshake challenge-response over the
+/// already-upgraded websocket, before any sync protocol frames are
+/// exchanged. Returns `None` if the client can't prove it knows the
+/// network key, or the connection drops mid-handshake; callers must treat
+/// that as "reject the connection".
+///
+/// Authenticates and encrypts the session, but does not provide forward
+/// secrecy: the session key is derived from the static network key plus
+/// two cleartext nonces, with no ephemeral DH step, so a compromised
+/// network key also compromises every past session an eavesdropper
+/// captured.
+pub async fn server_handshake<Sender, Receiver>(
+    connection: &mut Connection<Sender, Receiver>,
+    params: &HandshakeParams,
+) -> Option<(Encryptor, Decryptor)>
+where
+    Sender: Sink<Message> + Unpin,
+    Receiver: Stream<Item = Result<Message, axum::Error>> + Unpin,
+{
+    // 1. Client sends a random nonce to challenge against.
+    let client_nonce = match connection.receiver.next().await {
+        Some(Ok(Message::Binary(bytes))) if bytes.len() == NETWORK_KEY_LEN => bytes,
+        _ => return None,
+    };
+
+    // 2. Server proves it knows the network key for the client's nonce,
+    // and issues its own nonce for the client to prove the same back.
+    let mut server_nonce = [0u8; NETWORK_KEY_LEN];
+    rand::thread_rng().fill_bytes(&mut server_nonce);
+
+    let server_proof = hmac(&params.network_key, &client_nonce);
+    let mut reply = Vec::with_capacity(NETWORK_KEY_LEN * 2);
+    reply.extend_from_slice(&server_nonce);
+    reply.extend_from_slice(&server_proof);
+    connection.sender.send(Message::Binary(reply)).await.ok()?;
+
+    // 3. Client proves it knows the network key for the server's nonce.
+    let client_proof = match connection.receiver.next().await {
+        Some(Ok(Message::Binary(bytes))) => bytes,
+        _ => return None,
+    };
+    let expected = hmac(&params.network_key, &server_nonce);
+    // A non-constant-time comparison here would leak, via timing, how many
+    // leading bytes of the proof a guesser got right — this is the one
+    // check that gates authentication, so it has to be constant-time.
+    if client_proof.as_slice().ct_eq(expected.as_slice()).unwrap_u8() == 0 {
+        tracing::warn!("handshake: client failed network key challenge");
+        return None;
+    }
+
+    // 4. Both sides now derive the same session key from the two nonces
+    // and the network key, then split it into independent per-direction
+    // encryption keys.
+    //
+    // Known limitation: both nonces cross the wire in cleartext and there's
+    // no ephemeral DH step, so this has no forward secrecy, unlike the
+    // kuska-handshake/secret-handshake design it's modeled on. If the
+    // static network key is ever compromised, every past session whose
+    // nonces were captured can be decrypted retroactively.
+    let mut session_key = Vec::with_capacity(NETWORK_KEY_LEN * 3);
+    session_key.extend_from_slice(&params.network_key);
+    session_key.extend_from_slice(&client_nonce);
+    session_key.extend_from_slice(&server_nonce);
+
+    Some(split_session_key(&session_key, true))
+}
+
+fn hmac(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::channel::mpsc;
+
+    use super::*;
+
+    /// Drives the client side of the challenge-response over the other end
+    /// of `conn`'s channels, returning the session keys it derives so the
+    /// test can check they match the server's.
+    async fn run_client_side<Sender, Receiver>(
+        conn: &mut Connection<Sender, Receiver>,
+        network_key: &[u8; NETWORK_KEY_LEN],
+    ) -> Option<(Encryptor, Decryptor)>
+    where
+        Sender: Sink<Message> + Unpin,
+        Receiver: Stream<Item = Result<Message, axum::Error>> + Unpin,
+    {
+        let mut client_nonce = [0u8; NETWORK_KEY_LEN];
+        rand::thread_rng().fill_bytes(&mut client_nonce);
+        conn.sender.send(Message::Binary(client_nonce.to_vec())).await.ok()?;
+
+        let Some(Ok(Message::Binary(reply))) = conn.receiver.next().await else {
+            return None;
+        };
+        // A real client would also verify `server_proof` here before
+        // continuing; this harness skips that so a "wrong key" test can
+        // still drive the rest of the exchange and let the server's own
+        // `ct_eq` check be the thing under test.
+        let (server_nonce, _server_proof) = reply.split_at(NETWORK_KEY_LEN);
+
+        let client_proof = hmac(network_key, server_nonce);
+        conn.sender.send(Message::Binary(client_proof)).await.ok()?;
+
+        let mut session_key = Vec::with_capacity(NETWORK_KEY_LEN * 3);
+        session_key.extend_from_slice(network_key);
+        session_key.extend_from_slice(&client_nonce);
+        session_key.extend_from_slice(server_nonce);
+
+        Some(split_session_key(&session_key, false))
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn channel_pair() -> (
+        Connection<mpsc::UnboundedSender<Message>, impl Stream<Item = Result<Message, axum::Error>> + Unpin>,
+        Connection<mpsc::UnboundedSender<Message>, impl Stream<Item = Result<Message, axum::Error>> + Unpin>,
+    ) {
+        let (c2s_tx, c2s_rx) = mpsc::unbounded::<Message>();
+        let (s2c_tx, s2c_rx) = mpsc::unbounded::<Message>();
+
+        let server = Connection { sender: s2c_tx, receiver: c2s_rx.map(Ok::<_, axum::Error>) };
+        let client = Connection { sender: c2s_tx, receiver: s2c_rx.map(Ok::<_, axum::Error>) };
+        (server, client)
+    }
+
+    #[tokio::test]
+    async fn handshake_succeeds_and_derives_usable_session_keys() {
+        let network_key = [7u8; NETWORK_KEY_LEN];
+        let params = HandshakeParams::new(network_key);
+        let (mut server_conn, mut client_conn) = channel_pair();
+
+        let (server_result, client_result) = tokio::join!(
+            server_handshake(&mut server_conn, &params),
+            run_client_side(&mut client_conn, &network_key)
+        );
+
+        let (mut server_enc, mut server_dec) = server_result.expect("server should accept a valid proof");
+        let (mut client_enc, mut client_dec) = client_result.expect("client should derive matching keys");
+
+        let plaintext = b"hello from the server";
+        let ciphertext = server_enc.encrypt(plaintext);
+        assert_eq!(client_dec.decrypt(&ciphertext).as_deref(), Some(plaintext.as_slice()));
+
+        let plaintext = b"hello from the client";
+        let ciphertext = client_enc.encrypt(plaintext);
+        assert_eq!(server_dec.decrypt(&ciphertext).as_deref(), Some(plaintext.as_slice()));
+    }
+
+    #[tokio::test]
+    async fn handshake_rejects_wrong_network_key() {
+        let params = HandshakeParams::new([7u8; NETWORK_KEY_LEN]);
+        let wrong_key = [9u8; NETWORK_KEY_LEN];
+        let (mut server_conn, mut client_conn) = channel_pair();
+
+        let (server_result, _) = tokio::join!(
+            server_handshake(&mut server_conn, &params),
+            run_client_side(&mut client_conn, &wrong_key)
+        );
+
+        assert!(server_result.is_none());
+    }
+
+    #[tokio::test]
+    async fn handshake_rejects_malformed_first_message() {
+        let params = HandshakeParams::new([7u8; NETWORK_KEY_LEN]);
+        let (mut server_conn, mut client_conn) = channel_pair();
+
+        client_conn.sender.send(Message::Binary(vec![1, 2, 3])).await.unwrap();
+
+        assert!(server_handshake(&mut server_conn, &params).await.is_none());
+    }
+}
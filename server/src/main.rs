@@ -1,9 +1,13 @@
+mod chunking;
 mod editor;
+mod grpc;
+mod handshake;
+mod persistence;
 
-use std::net::SocketAddr;
+use std::{net::SocketAddr, sync::Arc};
 
 use axum::{
-    extract::{ConnectInfo, WebSocketUpgrade},
+    extract::{ConnectInfo, Path, WebSocketUpgrade},
     response::Response,
     routing::get,
     Extension, Router,
@@ -11,6 +15,10 @@ use axum::{
 use axum_prometheus::PrometheusMetricLayer;
 use editor::{spawn_client, spawn_server, ClientParams, Connection, ServerHandle, ServerParams};
 use futures::StreamExt;
+use grpc::{editor_server::EditorServer, EditorService};
+use handshake::{server_handshake, HandshakeParams, NETWORK_KEY_LEN};
+use persistence::FsPersistence;
+use tonic::transport::Server as GrpcServer;
 use tracing_subscriber::prelude::*;
 use uuid::Uuid;
 
@@ -38,7 +46,14 @@ async fn main() {
 
     let (prometheus_layer, metric_handle) = PrometheusMetricLayer::pair();
 
-    let (server, join) = spawn_server(ServerParams { capacity: None });
+    let persistence = Arc::new(FsPersistence::new("./data"));
+    let handshake = network_key_from_env().map(|key| Arc::new(HandshakeParams::new(key)));
+
+    let (server, join) = spawn_server(ServerParams {
+        capacity: None,
+        persistence: Some(persistence),
+        handshake,
+    });
 
     let asset_router = Router::new();
 
@@ -47,9 +62,11 @@ async fn main() {
         .route("/", get(index))
         .route("/assets/*path", get(serve_static));
 
+    let grpc_server_handle = server.clone();
+
     let app = Router::new()
         .merge(asset_router)
-        .route("/websocket", get(handle_websocket))
+        .route("/websocket/:room_id", get(handle_websocket))
         .route("/metrics", get(|| async move { metric_handle.render() }))
         .layer(prometheus_layer)
         .layer(Extension(server));
@@ -60,8 +77,14 @@ async fn main() {
 
     let serve = axum::serve(listener, app);
 
-    _ = tokio::join!(join, async {
-        serve.await.unwrap();
+    let grpc_addr: SocketAddr = "127.0.0.1:50051".parse().unwrap();
+    tracing::info!("listening on grpc://{grpc_addr}");
+    let serve_grpc = GrpcServer::builder()
+        .add_service(EditorServer::new(EditorService::new(grpc_server_handle)))
+        .serve(grpc_addr);
+
+    _ = tokio::join!(join, async { serve.await.unwrap() }, async {
+        serve_grpc.await.unwrap()
     });
 }
 
@@ -103,17 +126,56 @@ async fn serve_static(axum::extract::Path(path): axum::extract::Path<String>) ->
 
 async fn handle_websocket(
     ws: WebSocketUpgrade,
+    Path(room_id): Path<String>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
     Extension(server_handle): Extension<ServerHandle>,
 ) -> Response {
     ws.on_upgrade(move |socket| async move {
         let (sender, receiver) = socket.split();
+        let mut connection = Connection { sender, receiver };
+
+        let encryption = match &server_handle.handshake {
+            Some(params) => match server_handshake(&mut connection, params).await {
+                Some(keys) => Some(keys),
+                None => {
+                    tracing::warn!("handshake: rejecting connection from {addr} that failed the handshake");
+                    return;
+                }
+            },
+            None => None,
+        };
+
         spawn_client(ClientParams {
             id: Uuid::now_v7(),
             ip: addr,
+            room_id,
             server_handle,
-            connection: Connection { sender, receiver },
+            connection,
             capacity: None,
+            encryption,
         });
     })
 }
+
+/// Reads the secret-handshake network key from `SIMPLE_Y_CRDT_NETWORK_KEY`
+/// as a 64-character hex string. Unset means the handshake is disabled and
+/// clients are joined straight away, which is what local/unauthenticated
+/// use expects.
+fn network_key_from_env() -> Option<[u8; NETWORK_KEY_LEN]> {
+    let hex = std::env::var("SIMPLE_Y_CRDT_NETWORK_KEY").ok()?;
+    let bytes = hex_decode(&hex)?;
+    bytes.try_into().ok()
+}
+
+fn hex_decode(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    // Safe to slice by byte offset now that every byte is verified ASCII:
+    // each `s[i..i+2]` falls on a char boundary since ASCII bytes are always
+    // one byte wide.
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
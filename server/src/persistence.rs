@@ -0,0 +1,269 @@
+use std::{
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// Pluggable durability for a room's document. The default implementation
+/// below keeps things on the local filesystem, but operators can swap in
+/// any other backend (S3, a database, ...) by implementing this trait.
+pub trait Persistence: Send + Sync {
+    /// Load the persisted state for a room, if any, as a single `yrs`
+    /// update ready to be applied via `Doc::transact_mut().apply_update(...)`.
+    fn load(&self, room_id: &str) -> Option<Vec<u8>>;
+    /// Append an incoming document update to the room's durable log.
+    fn append(&self, room_id: &str, update: &[u8]);
+    /// Replace the room's log with a single compacted snapshot.
+    fn snapshot(&self, room_id: &str, state: &[u8]);
+}
+
+/// Percent-encodes everything outside `[A-Za-z0-9_-]` as `%XX` (uppercase
+/// hex of the UTF-8 byte), so distinct room ids never collide on the same
+/// encoded directory name.
+fn percent_encode_room_id(room_id: &str) -> String {
+    let mut encoded = String::with_capacity(room_id.len());
+    for byte in room_id.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+/// Filesystem-backed [`Persistence`]: one snapshot file and one append-only
+/// log file per room, both under `dir`.
+#[derive(Debug, Clone)]
+pub struct FsPersistence {
+    dir: PathBuf,
+}
+
+impl FsPersistence {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        let dir = dir.into();
+        if let Err(e) = fs::create_dir_all(&dir) {
+            tracing::error!("persistence: failed to create {:?}: {:?}", dir, e);
+        }
+        Self { dir }
+    }
+
+    fn room_dir(&self, room_id: &str) -> PathBuf {
+        // Room ids come straight from the URL, so keep them from escaping
+        // the persistence directory. Percent-encode anything outside the
+        // safe set instead of folding it to `_`: collapsing distinct ids
+        // (e.g. "a.b" and "a_b") onto the same directory would make two
+        // rooms silently share one snapshot/log.
+        self.dir.join(percent_encode_room_id(room_id))
+    }
+
+    fn snapshot_path(&self, room_id: &str) -> PathBuf {
+        self.room_dir(room_id).join("snapshot.bin")
+    }
+
+    fn log_path(&self, room_id: &str) -> PathBuf {
+        self.room_dir(room_id).join("log.bin")
+    }
+
+    /// The log is a sequence of `[len: u32 LE][update bytes]` frames.
+    fn read_log_updates(path: &Path) -> Vec<Vec<u8>> {
+        let Ok(bytes) = fs::read(path) else {
+            return Vec::new();
+        };
+
+        let mut updates = Vec::new();
+        let mut rest = bytes.as_slice();
+        while rest.len() >= 4 {
+            let (len_bytes, tail) = rest.split_at(4);
+            let len = u32::from_le_bytes(len_bytes.try_into().unwrap()) as usize;
+            if tail.len() < len {
+                tracing::warn!("persistence: truncated log entry in {:?}, stopping replay", path);
+                break;
+            }
+            let (update, tail) = tail.split_at(len);
+            updates.push(update.to_vec());
+            rest = tail;
+        }
+        updates
+    }
+}
+
+impl Persistence for FsPersistence {
+    fn load(&self, room_id: &str) -> Option<Vec<u8>> {
+        use yrs::{updates::decoder::Decode, Doc, ReadTxn, StateVector, Transact, Update};
+
+        let snapshot = fs::read(self.snapshot_path(room_id)).ok();
+        let updates = Self::read_log_updates(&self.log_path(room_id));
+
+        if snapshot.is_none() && updates.is_empty() {
+            return None;
+        }
+
+        let doc = Doc::new();
+        {
+            let mut txn = doc.transact_mut();
+            if let Some(snapshot) = snapshot {
+                match Update::decode_v1(&snapshot) {
+                    Ok(update) => {
+                        if let Err(e) = txn.apply_update(update) {
+                            tracing::error!("persistence: failed to apply snapshot for {room_id}: {:?}", e);
+                        }
+                    }
+                    Err(e) => tracing::error!("persistence: corrupt snapshot for {room_id}: {:?}", e),
+                }
+            }
+            for update in updates {
+                match Update::decode_v1(&update) {
+                    Ok(update) => {
+                        if let Err(e) = txn.apply_update(update) {
+                            tracing::error!("persistence: failed to replay log entry for {room_id}: {:?}", e);
+                        }
+                    }
+                    Err(e) => tracing::error!("persistence: corrupt log entry for {room_id}: {:?}", e),
+                }
+            }
+        }
+
+        let txn = doc.transact();
+        Some(txn.encode_state_as_update_v1(&StateVector::default()))
+    }
+
+    fn append(&self, room_id: &str, update: &[u8]) {
+        let dir = self.room_dir(room_id);
+        if let Err(e) = fs::create_dir_all(&dir) {
+            tracing::error!("persistence: failed to create {:?}: {:?}", dir, e);
+            return;
+        }
+
+        let path = self.log_path(room_id);
+        let result = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|mut file| {
+                file.write_all(&(update.len() as u32).to_le_bytes())?;
+                file.write_all(update)
+            });
+
+        if let Err(e) = result {
+            tracing::error!("persistence: failed to append update for {room_id}: {:?}", e);
+        }
+    }
+
+    fn snapshot(&self, room_id: &str, state: &[u8]) {
+        let dir = self.room_dir(room_id);
+        if let Err(e) = fs::create_dir_all(&dir) {
+            tracing::error!("persistence: failed to create {:?}: {:?}", dir, e);
+            return;
+        }
+
+        let tmp_path = self.snapshot_path(room_id).with_extension("bin.tmp");
+        let result = fs::write(&tmp_path, state).and_then(|_| fs::rename(&tmp_path, self.snapshot_path(room_id)));
+
+        match result {
+            Ok(()) => {
+                if let Err(e) = fs::remove_file(self.log_path(room_id)) {
+                    if e.kind() != std::io::ErrorKind::NotFound {
+                        tracing::error!("persistence: failed to truncate log for {room_id}: {:?}", e);
+                    }
+                }
+            }
+            Err(e) => tracing::error!("persistence: failed to write snapshot for {room_id}: {:?}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use yrs::{updates::decoder::Decode, Doc, GetString, ReadTxn, StateVector, Text, Transact, Update};
+
+    use super::*;
+
+    /// A directory under the OS temp dir that's removed when the guard
+    /// drops, so tests don't leak files into one another.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("simple-y-crdt-editor-test-{}", uuid::Uuid::now_v7()));
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn encode_update(text: &str) -> Vec<u8> {
+        let doc = Doc::new();
+        let content = doc.get_or_insert_text("content");
+        content.insert(&mut doc.transact_mut(), 0, text);
+        let txn = doc.transact();
+        txn.encode_state_as_update_v1(&StateVector::default())
+    }
+
+    fn decode_content(update: &[u8]) -> String {
+        let doc = Doc::new();
+        doc.transact_mut().apply_update(Update::decode_v1(update).unwrap()).unwrap();
+        let content = doc.get_or_insert_text("content");
+        let txn = doc.transact();
+        content.get_string(&txn)
+    }
+
+    #[test]
+    fn distinct_room_ids_never_collide_on_disk() {
+        let dir = TempDir::new();
+        let persistence = FsPersistence::new(&dir.0);
+
+        let paths: Vec<PathBuf> =
+            ["a.b", "a_b", "a b", "a/b", "a%b"].iter().map(|id| persistence.room_dir(id)).collect();
+
+        for (i, a) in paths.iter().enumerate() {
+            for (j, b) in paths.iter().enumerate() {
+                assert!(i == j || a != b, "room ids collided onto {a:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn room_dir_stays_inside_the_persistence_dir() {
+        let dir = TempDir::new();
+        let persistence = FsPersistence::new(&dir.0);
+
+        let room_dir = persistence.room_dir("../../etc");
+        assert!(room_dir.starts_with(&dir.0));
+    }
+
+    #[test]
+    fn load_returns_none_for_an_unknown_room() {
+        let dir = TempDir::new();
+        let persistence = FsPersistence::new(&dir.0);
+        assert!(persistence.load("never-seen").is_none());
+    }
+
+    #[test]
+    fn append_then_load_replays_the_log() {
+        let dir = TempDir::new();
+        let persistence = FsPersistence::new(&dir.0);
+
+        persistence.append("room-1", &encode_update("hello"));
+
+        let loaded = persistence.load("room-1").expect("log entry should be replayed");
+        assert_eq!(decode_content(&loaded), "hello");
+    }
+
+    #[test]
+    fn snapshot_then_load_reflects_the_snapshot_and_truncates_the_log() {
+        let dir = TempDir::new();
+        let persistence = FsPersistence::new(&dir.0);
+
+        persistence.append("room-1", &encode_update("stale"));
+        persistence.snapshot("room-1", &encode_update("snapshotted"));
+
+        assert!(!persistence.log_path("room-1").exists(), "snapshot should truncate the log");
+
+        let loaded = persistence.load("room-1").expect("snapshot should be loaded");
+        assert_eq!(decode_content(&loaded), "snapshotted");
+    }
+}